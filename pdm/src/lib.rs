@@ -1,11 +1,22 @@
 #![no_std]
-#![feature(array_chunks)]
 
 #[cfg(feature = "libm")]
 use core::f64::consts::PI;
 use heapless::Vec;
 #[cfg(feature = "libm")]
-use libm::sin;
+use libm::{cos, sin};
+#[cfg(feature = "dither")]
+use rand_core::RngCore;
+
+/// Number of taps on each side of the windowed-sinc resampling kernel used
+/// by [`generate_from_samples`].
+#[cfg(feature = "libm")]
+const RESAMPLE_HALF_TAPS: usize = 4;
+
+/// Number of fractional-delay phases in the sinc sub-filter table used by
+/// [`generate_from_samples`].
+#[cfg(feature = "libm")]
+const RESAMPLE_PHASES: usize = 32;
 
 /// **PDM** modulation based on pseudo code from
 /// <https://en.wikipedia.org/wiki/Pulse-density_modulation>
@@ -30,25 +41,57 @@ use libm::sin;
 /// assert_eq!(pdm[1], 0b00110011);
 /// ```
 pub fn generate<const N: usize, const N_8: usize>(curve: fn(usize) -> f64) -> Vec<u8, N_8> {
-    assert_eq!(N % 8, 0);
-    let mut qe = 0.0;
-    let x = (0..N)
-        .map(curve)
-        .map(|v| {
-            qe += v;
-            if qe > 0.0 {
-                qe -= 1.0;
+    stream::<N, _>(curve).collect()
+}
+
+/// Lazy, single-bit sigma-delta iterator that packs 8 bits into a byte on
+/// the fly, generic over any per-sample `FnMut(usize) -> f64` source.
+/// Built via [`stream`].
+pub struct PdmBits<F> {
+    source: F,
+    qe: f64,
+    index: usize,
+    n: usize,
+}
+
+impl<F: FnMut(usize) -> f64> Iterator for PdmBits<F> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.index >= self.n {
+            return None;
+        }
+        let mut byte = 0u8;
+        for _ in 0..8 {
+            self.qe += (self.source)(self.index);
+            let bit = if self.qe > 0.0 {
+                self.qe -= 1.0;
                 1_u8
             } else {
-                qe += 1.0;
+                self.qe += 1.0;
                 0_u8
-            }
-        })
-        .collect::<Vec<u8, N>>()
-        .array_chunks::<8>()
-        .map(|x| x.iter().fold(0u8, |res, b| (res << 1) ^ *b))
-        .collect::<Vec<u8, N_8>>();
-    x
+            };
+            byte = (byte << 1) ^ bit;
+            self.index += 1;
+        }
+        Some(byte)
+    }
+}
+
+/// Streams `source` through the first-order modulator and yields one
+/// packed byte at a time, with `O(1)` additional memory beyond the
+/// modulator state. `generate` is `stream::<N, _>(curve).collect()`.
+///
+/// - **N** is the number of PDM bits, same as in [`generate`], and must be
+///   a multiple of 8.
+pub fn stream<const N: usize, F: FnMut(usize) -> f64>(source: F) -> PdmBits<F> {
+    assert_eq!(N % 8, 0);
+    PdmBits {
+        source,
+        qe: 0.0,
+        index: 0,
+        n: N,
+    }
 }
 
 /// square wave function
@@ -69,6 +112,170 @@ pub fn sine_idx<const N: usize>(index: usize) -> f64 {
     sin((index as f64) / (N as f64) * 2.0 * PI)
 }
 
+/// Higher-order sigma-delta modulator: `ORDER == 1` matches [`generate`],
+/// `2` feeds back `2*e1 - e2`, `3` feeds back `3*e1 - 3*e2 + e3`. Other
+/// values of `ORDER` panic.
+pub fn generate_order<const ORDER: usize, const N: usize, const N_8: usize>(
+    curve: fn(usize) -> f64,
+) -> Vec<u8, N_8> {
+    assert_eq!(N % 8, 0);
+    match ORDER {
+        1 => generate::<N, N_8>(curve),
+        2 => HigherOrderBits::<_, 2> {
+            curve,
+            errors: [0.0; 2],
+            index: 0,
+            n: N,
+        }
+        .collect(),
+        3 => HigherOrderBits::<_, 3> {
+            curve,
+            errors: [0.0; 3],
+            index: 0,
+            n: N,
+        }
+        .collect(),
+        _ => panic!("unsupported modulator order (supported: 1, 2, 3)"),
+    }
+}
+
+/// Lazy packed-byte iterator behind the `ORDER == 2`/`3` arms of
+/// [`generate_order`], mirroring how [`PdmBits`] backs `generate`.
+struct HigherOrderBits<F, const ORDER: usize> {
+    curve: F,
+    errors: [f64; ORDER],
+    index: usize,
+    n: usize,
+}
+
+impl<F: FnMut(usize) -> f64, const ORDER: usize> Iterator for HigherOrderBits<F, ORDER> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.index >= self.n {
+            return None;
+        }
+        let mut byte = 0u8;
+        for _ in 0..8 {
+            let x = (self.curve)(self.index);
+            let u = match ORDER {
+                2 => x + 2.0 * self.errors[0] - self.errors[1],
+                3 => x + 3.0 * self.errors[0] - 3.0 * self.errors[1] + self.errors[2],
+                _ => unreachable!("HigherOrderBits only supports ORDER 2 or 3"),
+            };
+            let b = if u >= 0.0 { 1_u8 } else { 0_u8 };
+            let d = if b == 1 { 1.0 } else { -1.0 };
+            for k in (1..ORDER).rev() {
+                self.errors[k] = self.errors[k - 1];
+            }
+            self.errors[0] = d - u;
+            byte = (byte << 1) ^ b;
+            self.index += 1;
+        }
+        Some(byte)
+    }
+}
+
+/// First-order modulator with TPDF dither: draws `u1`, `u2` from `rng`
+/// and adds `(u1 + u2 - 1.0) * lsb` to each sample before the sign
+/// decision. `generate` itself stays deterministic.
+#[cfg(feature = "dither")]
+pub fn generate_dithered<const N: usize, const N_8: usize>(
+    curve: fn(usize) -> f64,
+    rng: &mut impl RngCore,
+    lsb: f64,
+) -> Vec<u8, N_8> {
+    stream::<N, _>(move |i| {
+        let u1 = (rng.next_u32() as f64) / (u32::MAX as f64 + 1.0);
+        let u2 = (rng.next_u32() as f64) / (u32::MAX as f64 + 1.0);
+        curve(i) + (u1 + u2 - 1.0) * lsb
+    })
+    .collect()
+}
+
+/// Normalized sinc: `sin(pi*x) / (pi*x)`, with the removable singularity
+/// at `x == 0.0` handled explicitly.
+#[cfg(feature = "libm")]
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        sin(PI * x) / (PI * x)
+    }
+}
+
+/// Blackman window, `n` ranging over `0.0..=len`.
+#[cfg(feature = "libm")]
+fn blackman(n: f64, len: f64) -> f64 {
+    0.42 - 0.5 * cos(2.0 * PI * n / len) + 0.08 * cos(4.0 * PI * n / len)
+}
+
+/// Windowed-sinc sub-filter table: `RESAMPLE_PHASES` fractional delays,
+/// each with `2 * RESAMPLE_HALF_TAPS` taps, used to interpolate between
+/// input samples at an arbitrary fractional position.
+#[cfg(feature = "libm")]
+fn sinc_table() -> [[f64; 2 * RESAMPLE_HALF_TAPS]; RESAMPLE_PHASES] {
+    let taps = 2 * RESAMPLE_HALF_TAPS;
+    let mut table = [[0.0; 2 * RESAMPLE_HALF_TAPS]; RESAMPLE_PHASES];
+    for (phase, row) in table.iter_mut().enumerate() {
+        let frac = phase as f64 / RESAMPLE_PHASES as f64;
+        for (k, tap) in row.iter_mut().enumerate() {
+            let n = k as f64 - (RESAMPLE_HALF_TAPS as f64 - 1.0) - frac;
+            *tap = sinc(n) * blackman(n + RESAMPLE_HALF_TAPS as f64, taps as f64);
+        }
+    }
+    table
+}
+
+/// Evaluates the band-limited interpolation of `samples` at fractional
+/// source index `p = n / ratio`, picking the sub-filter phase closest to
+/// `p`'s fractional part and convolving it against the nearest
+/// `2 * RESAMPLE_HALF_TAPS` input samples. Source indices that fall
+/// outside `samples` contribute nothing (they count as `0.0`).
+#[cfg(feature = "libm")]
+fn interpolate(
+    samples: &[f64],
+    table: &[[f64; 2 * RESAMPLE_HALF_TAPS]; RESAMPLE_PHASES],
+    n: usize,
+    ratio: f64,
+) -> f64 {
+    let p = n as f64 / ratio;
+    let mut base = libm::floor(p) as isize;
+    let frac = p - base as f64;
+    let mut phase = libm::round(frac * RESAMPLE_PHASES as f64) as isize;
+    if phase == RESAMPLE_PHASES as isize {
+        // rounded up into the next integer sample - shift the phase back
+        // to 0 and advance `base` so the kernel stays centered on `p`.
+        phase = 0;
+        base += 1;
+    }
+    let phase = phase as usize;
+    table[phase]
+        .iter()
+        .enumerate()
+        .map(|(k, coeff)| {
+            let idx = base - (RESAMPLE_HALF_TAPS as isize - 1) + k as isize;
+            if idx >= 0 && (idx as usize) < samples.len() {
+                coeff * samples[idx as usize]
+            } else {
+                0.0
+            }
+        })
+        .sum()
+}
+
+/// Resamples `samples` up to the PDM rate via windowed-sinc interpolation
+/// and feeds the result through the first-order modulator. `ratio` is
+/// `pdm_rate / src_rate`. **N** / **N_8** are as in [`generate`].
+#[cfg(feature = "libm")]
+pub fn generate_from_samples<const N: usize, const N_8: usize>(
+    samples: &[f64],
+    ratio: f64,
+) -> Vec<u8, N_8> {
+    let table = sinc_table();
+    stream::<N, _>(move |n| interpolate(samples, &table, n, ratio)).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -85,6 +292,103 @@ mod tests {
         ));
     }
 
+    #[cfg(feature = "dither")]
+    struct XorShift32(u32);
+
+    #[cfg(feature = "dither")]
+    impl RngCore for XorShift32 {
+        fn next_u32(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            (self.next_u32() as u64) << 32 | self.next_u32() as u64
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            for chunk in dest.chunks_mut(4) {
+                chunk.copy_from_slice(&self.next_u32().to_le_bytes()[..chunk.len()]);
+            }
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "dither")]
+    fn dithered_golden_bytes() {
+        let mut rng = XorShift32(0x1234_5678);
+        let dithered: [u8; 4] = generate_dithered::<32, 4>(square_idx::<6>, &mut rng, 1.0)
+            .as_slice()
+            .try_into()
+            .unwrap();
+        assert!(matches!(
+            dithered,
+            [0b00011100, 0b01111001, 0b11100110, 0b00011100]
+        ));
+    }
+
+    #[test]
+    fn stream_matches_generate() {
+        let streamed: Vec<u8, 4> = stream::<32, _>(square_idx::<6>).collect();
+        assert_eq!(streamed, generate::<32, 4>(square_idx::<6>));
+    }
+
+    #[test]
+    fn order_1_matches_generate() {
+        assert_eq!(
+            generate_order::<1, 32, 4>(square_idx::<6>),
+            generate::<32, 4>(square_idx::<6>)
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn unsupported_order_panics() {
+        generate_order::<4, 32, 4>(square_idx::<6>);
+    }
+
+    /// 16-sample sawtooth ramp from -1.0 to ~1.0, used to pin golden byte
+    /// values for the order-2/3 feedback math: unlike a square wave it
+    /// keeps `u` close to the decision boundary over the first few samples
+    /// (at order 2 one sample lands exactly on `0.0`), so a transposed
+    /// `e1`/`e2`/`e3` update or wrong `d` polarity changes the output.
+    /// After that the error states grow without bound and the bits
+    /// alternate, which is why the later bytes are all `0b10101010`.
+    fn ramp_idx(index: usize) -> f64 {
+        (index % 16) as f64 / 8.0 - 1.0
+    }
+
+    #[test]
+    fn order_2_golden_bytes() {
+        let order2: [u8; 4] = generate_order::<2, 32, 4>(ramp_idx)
+            .as_slice()
+            .try_into()
+            .unwrap();
+        assert!(matches!(
+            order2,
+            [0b00000101, 0b10101010, 0b10101010, 0b10101010]
+        ));
+    }
+
+    #[test]
+    fn order_3_golden_bytes() {
+        let order3: [u8; 4] = generate_order::<3, 32, 4>(ramp_idx)
+            .as_slice()
+            .try_into()
+            .unwrap();
+        assert!(matches!(
+            order3,
+            [0b00011010, 0b10101010, 0b10101010, 0b10101010]
+        ));
+    }
+
     #[test]
     #[cfg(feature = "libm")]
     fn sine_wave() {
@@ -97,4 +401,41 @@ mod tests {
             [0b01110111, 0b11111101, 0b10010000, 0b00000010]
         ));
     }
+
+    #[test]
+    #[cfg(feature = "libm")]
+    fn resampled_matches_direct_curve_at_unity_ratio() {
+        let samples: Vec<f64, 32> = (0..32).map(square_idx::<6>).collect();
+        let resampled: Vec<u8, 4> = generate_from_samples::<32, 4>(&samples, 1.0);
+        assert_eq!(resampled, generate::<32, 4>(square_idx::<6>));
+    }
+
+    #[test]
+    #[cfg(feature = "libm")]
+    fn resampled_upsample_golden_bytes() {
+        // 8 low-rate samples, two square-wave half-periods; ratio 2.0
+        // upsamples to 16 PDM bits, actually exercising the sinc kernel.
+        let samples: Vec<f64, 8> = [-1.0, -1.0, -1.0, -1.0, 1.0, 1.0, 1.0, 1.0]
+            .into_iter()
+            .collect();
+        let upsampled: [u8; 2] = generate_from_samples::<16, 2>(&samples, 2.0)
+            .as_slice()
+            .try_into()
+            .unwrap();
+        assert!(matches!(upsampled, [0b00000000, 0b11111111]));
+    }
+
+    #[test]
+    #[cfg(feature = "libm")]
+    fn resampled_downsample_golden_bytes() {
+        // 16 high-rate samples, ratio 0.5 downsamples to 8 PDM bits.
+        let samples: Vec<f64, 16> = (0..16)
+            .map(|i: usize| if (i / 4) % 2 == 0 { -1.0 } else { 1.0 })
+            .collect();
+        let downsampled: [u8; 1] = generate_from_samples::<8, 1>(&samples, 0.5)
+            .as_slice()
+            .try_into()
+            .unwrap();
+        assert!(matches!(downsampled, [0b00110011]));
+    }
 }